@@ -0,0 +1,191 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::shell::Shell;
+use crate::template::{Variable, Variables};
+use crate::Profile;
+
+// Placeholders that are filled in from where okeydokey was invoked rather than
+// from arguments.
+pub const NAMES: &[&str] = &[
+    "profile_dir",
+    "cwd",
+    "rel",
+    "git_root",
+    "profile_file",
+    "os",
+];
+
+// Everything a named hole can be filled from besides the arguments: the
+// profile's own variables and then the placeholders above. Anything that has
+// to run a command to be worked out is only run once per invocation.
+pub struct Context {
+    profile_file: PathBuf,
+    current_directory: PathBuf,
+    variables: Vec<(String, String)>,
+    shell: String,
+    cache: RefCell<HashMap<String, Result<String, String>>>,
+    failures: RefCell<Vec<(String, String)>>,
+}
+
+impl Context {
+    pub fn new(profile: &Profile, shell: String) -> Context {
+        Context {
+            profile_file: profile.path.clone(),
+            current_directory: env::current_dir().unwrap_or_default(),
+            variables: profile.variables.clone(),
+            shell,
+            cache: RefCell::new(HashMap::new()),
+            failures: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn profile_directory(&self) -> &Path {
+        self.profile_file.parent().unwrap()
+    }
+
+    pub fn defines(&self, name: &str) -> bool {
+        NAMES.contains(&name) || self.variable(name).is_some()
+    }
+
+    // A variable set more than once in the profile takes the last value.
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        self.variables
+            .iter()
+            .rev()
+            .find(|(variable, _)| variable == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Values are worked out when asked for so that git is only run for
+    // commands that use {git_root}.
+    fn built_in(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "profile_dir" => Ok(display(self.profile_directory())),
+            "cwd" => Ok(display(&self.current_directory)),
+            "rel" => self.relative_directory(),
+            "git_root" => self.cached(name, || self.git_root()),
+            "profile_file" => Ok(display(&self.profile_file)),
+            "os" => Ok(env::consts::OS.to_string()),
+            _ => return None,
+        };
+        self.record(name, value)
+    }
+
+    fn relative_directory(&self) -> Result<String, String> {
+        let relative = self
+            .current_directory
+            .strip_prefix(self.profile_directory())
+            .map_err(|_| {
+                format!(
+                    "{} is not inside {}",
+                    self.current_directory.display(),
+                    self.profile_directory().display()
+                )
+            })?;
+        match relative.as_os_str().is_empty() {
+            true => Ok(".".to_string()),
+            false => Ok(display(relative)),
+        }
+    }
+
+    fn git_root(&self) -> Result<String, String> {
+        let output = Command::new("git")
+            .args(["rev-parse", "--show-toplevel"])
+            .current_dir(&self.current_directory)
+            .output()
+            .map_err(|error| format!("could not run git: {}", error))?;
+        match output.status.success() {
+            true => Ok(String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_string()),
+            false => Err(format!(
+                "{} is not inside a git repository",
+                self.current_directory.display()
+            )),
+        }
+    }
+
+    // Keeps the reason a value couldn't be worked out so that it is reported
+    // rather than the hole being left unfilled. This happens on every use, even
+    // of a cached value, so that forgetting the failures doesn't lose any.
+    fn record(&self, name: &str, value: Result<String, String>) -> Option<String> {
+        match value {
+            Ok(value) => Some(value),
+            Err(reason) => {
+                self.failures.borrow_mut().push((name.to_string(), reason));
+                None
+            }
+        }
+    }
+
+    // The first variable or placeholder that couldn't be worked out and why. Filling in holes
+    // doesn't stop for it, so this has to be checked before the result is used.
+    pub fn failure(&self) -> Option<(String, String)> {
+        self.failures.borrow().first().cloned()
+    }
+
+    // The picker fills in its preview from the context, which shouldn't fail a
+    // command that is picked afterwards.
+    pub fn forget_failures(&self) {
+        self.failures.borrow_mut().clear();
+    }
+
+    // Variables written $(command) are filled with what the command prints,
+    // run by the shell in the profile's directory as --run would.
+    fn evaluate(&self, command: &str) -> Result<String, String> {
+        let output = Command::new(&self.shell)
+            .arg(Shell::from_name(&self.shell).command_flag())
+            .arg(command)
+            .current_dir(self.profile_directory())
+            .stderr(Stdio::inherit())
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                let value = String::from_utf8_lossy(&output.stdout);
+                Ok(value.trim_end_matches(['\r', '\n']).to_string())
+            }
+            Ok(output) => Err(format!("`{}` exited with {}", command, output.status)),
+            Err(error) => Err(format!("could not run {}: {}", self.shell, error)),
+        }
+    }
+
+    fn cached(
+        &self,
+        name: &str,
+        work_out: impl FnOnce() -> Result<String, String>,
+    ) -> Result<String, String> {
+        if let Some(value) = self.cache.borrow().get(name) {
+            return value.clone();
+        }
+        let value = work_out();
+        self.cache
+            .borrow_mut()
+            .insert(name.to_string(), value.clone());
+        value
+    }
+}
+
+impl Variables for Context {
+    fn get(&self, name: &str) -> Option<Variable> {
+        let Some(value) = self.variable(name) else {
+            return self.built_in(name).map(Variable::Found);
+        };
+        match value
+            .strip_prefix("$(")
+            .and_then(|value| value.strip_suffix(')'))
+        {
+            Some(command) => self
+                .record(name, self.cached(name, || self.evaluate(command)))
+                .map(Variable::Found),
+            None => Some(Variable::Default(value.to_string())),
+        }
+    }
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::path::{self, Path};
+
+use crate::shell::Shell;
+
+// Written after a hole as in {0|upper}. Filters run in the order they are
+// written, each on every word the hole is filled with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Raw,
+    Quote,
+    Upper,
+    Lower,
+    Slug,
+    Abs,
+    Basename,
+    Join(String),
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Option<Filter> {
+        match text {
+            "raw" => Some(Filter::Raw),
+            "quote" => Some(Filter::Quote),
+            "upper" => Some(Filter::Upper),
+            "lower" => Some(Filter::Lower),
+            "slug" => Some(Filter::Slug),
+            "abs" => Some(Filter::Abs),
+            "basename" => Some(Filter::Basename),
+            "join" => Some(Filter::Join(" ".to_string())),
+            _ => text
+                .strip_prefix("join:")
+                .map(|separator| Filter::Join(separator.to_string())),
+        }
+    }
+
+    // Whether the filter decides the quoting itself, so the value shouldn't be
+    // quoted again once the filters have run.
+    pub fn quotes(&self) -> bool {
+        matches!(self, Filter::Raw | Filter::Quote)
+    }
+
+    // Quote quotes for the given shell even when arguments otherwise wouldn't
+    // be, falling back to POSIX quoting when there is none.
+    pub fn apply(&self, words: Vec<String>, shell: Option<Shell>) -> Vec<String> {
+        let each = |transform: &dyn Fn(&str) -> String| {
+            words.iter().map(|word| transform(word)).collect::<Vec<_>>()
+        };
+        match self {
+            Filter::Raw => words,
+            Filter::Quote => each(&|word| shell.unwrap_or(Shell::Posix).quote(word)),
+            Filter::Upper => each(&str::to_uppercase),
+            Filter::Lower => each(&str::to_lowercase),
+            Filter::Slug => each(&slug),
+            Filter::Abs => each(&|word| match path::absolute(word) {
+                Ok(absolute) => absolute.to_string_lossy().into_owned(),
+                Err(_) => word.to_string(),
+            }),
+            Filter::Basename => each(&|word| match Path::new(word).file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => word.to_string(),
+            }),
+            Filter::Join(separator) => vec![words.join(separator)],
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::Raw => write!(f, "raw"),
+            Filter::Quote => write!(f, "quote"),
+            Filter::Upper => write!(f, "upper"),
+            Filter::Lower => write!(f, "lower"),
+            Filter::Slug => write!(f, "slug"),
+            Filter::Abs => write!(f, "abs"),
+            Filter::Basename => write!(f, "basename"),
+            Filter::Join(separator) => write!(f, "join:{}", separator),
+        }
+    }
+}
+
+// Lower case with every run of other characters turned into a single dash, so
+// feature/Add Login becomes feature-add-login.
+fn slug(word: &str) -> String {
+    let mut slug = String::new();
+    for character in word.chars() {
+        if character.is_alphanumeric() {
+            slug.extend(character.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
@@ -0,0 +1,112 @@
+use indoc::indoc;
+
+pub const SHELLS: &[&str] = &["pwsh", "bash", "zsh", "fish", "nu"];
+
+pub fn hook(shell: &str) -> Option<&'static str> {
+    match shell {
+        "pwsh" => Some(PWSH),
+        "bash" | "zsh" => Some(POSIX),
+        "fish" => Some(FISH),
+        "nu" => Some(NU),
+        _ => None,
+    }
+}
+
+const PWSH: &str = indoc! {r#"
+    function ok
+    {
+        if ($args.Count -eq 0) {
+            okeydokey | Write-Host -ForegroundColor 'Blue'
+        } else {
+            if ($args.Count -gt 1) {
+                $script = okeydokey $args[0] --shell pwsh -p "pushd {};" -s "; popd" -a ($args | select -skip 1)
+            } else {
+                $script = okeydokey $args[0] --shell pwsh -p "pushd {};" -s "; popd"
+            }
+
+            if ($script -ne $null) {
+                iex $script
+            }
+        }
+    }
+"#};
+
+// Shared between bash and zsh. The popd happens outside of the evaluated script
+// so that the wrapped command's exit status is the one returned from ok. When
+// okeydokey itself fails, its exit status is returned instead.
+const POSIX: &str = indoc! {r#"
+    ok() {
+        if [ $# -eq 0 ]; then
+            okeydokey
+            return
+        fi
+
+        local ok_script ok_status
+        if [ $# -gt 1 ]; then
+            ok_script=$(okeydokey "$1" --shell bash -p "pushd '{}' > /dev/null; " -a "${@:2}") || return $?
+        else
+            ok_script=$(okeydokey "$1" --shell bash -p "pushd '{}' > /dev/null; ") || return $?
+        fi
+
+        if [ -n "$ok_script" ]; then
+            eval "$ok_script"
+            ok_status=$?
+            popd > /dev/null
+            return $ok_status
+        fi
+    }
+"#};
+
+// okeydokey's output is captured without a pipe so that its exit status can be
+// returned when it fails. Completions are re-queried from okeydokey every time
+// so that they follow the nearest .ok file as the working directory changes.
+const FISH: &str = indoc! {r#"
+    function ok
+        if test (count $argv) -eq 0
+            set_color blue
+            okeydokey
+            set -l ok_status $status
+            set_color normal
+            return $ok_status
+        end
+
+        set -l ok_lines
+        if test (count $argv) -gt 1
+            set ok_lines (okeydokey $argv[1] --shell fish -p "pushd '{}'; " -a $argv[2..-1])
+        else
+            set ok_lines (okeydokey $argv[1] --shell fish -p "pushd '{}'; ")
+        end
+        or return $status
+
+        set -l ok_script (string join \n -- $ok_lines | string collect)
+
+        if test -n "$ok_script"
+            eval $ok_script
+            set -l ok_status $status
+            popd
+            return $ok_status
+        end
+    end
+
+    function __ok_commands
+        okeydokey | string replace -r '^(\S+)\s*' '$1'\t
+    end
+
+    complete -c ok -f
+    complete -c ok -n 'test (count (commandline -opc)) -eq 1' -a '(__ok_commands)'
+"#};
+
+// Listing returns a table rather than a string. The command itself is handed to
+// a child nu so that the cd into the profile directory does not leak out.
+const NU: &str = indoc! {r#"
+    def ok [...args: string] {
+        if ($args | is-empty) {
+            okeydokey --format json | from json | where not internal | select name description body
+        } else {
+            let script = (okeydokey ($args | first) --shell nu -p "cd '{}'; " -a ...($args | skip 1))
+            if not ($script | is-empty) {
+                nu -c $script
+            }
+        }
+    }
+"#};
@@ -1,19 +1,94 @@
 use std::env;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, ValueEnum};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::env::{CompleteEnv, Shells};
 use indoc::indoc;
+use serde_json::json;
+
+mod context;
+mod filter;
+mod hooks;
+mod matching;
+mod picker;
+mod shell;
+mod template;
+
+use context::Context;
+use matching::MatchOptions;
+use shell::Shell;
+use template::{fill_in_arguments, fill_in_variables, Filled, Hole};
+
+#[derive(Clone)]
+struct ProfileCommand {
+    name: String,
+    command: String,
+    description: Option<String>,
+    line: usize,
+}
+
+struct ProfileError {
+    path: PathBuf,
+    line: usize,
+    column: usize,
+    text: String,
+    reason: String,
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "error: {}", self.reason)?;
+        if self.line == 0 {
+            return write!(f, "  --> {}", self.path.display());
+        }
+
+        write!(
+            f,
+            "  --> {}:{}:{}",
+            self.path.display(),
+            self.line,
+            self.column
+        )?;
+        if !self.text.is_empty() {
+            let gutter = " ".repeat(self.line.to_string().len());
+            write!(
+                f,
+                "\n{} |\n{} | {}\n{} | {}^",
+                gutter,
+                self.line,
+                self.text,
+                gutter,
+                " ".repeat(self.column.saturating_sub(1))
+            )?;
+        }
+        Ok(())
+    }
+}
 
 struct Profile {
-    commands: Vec<(String, String)>,
-    internal_commands: Vec<(String, String)>,
+    commands: Vec<ProfileCommand>,
+    internal_commands: Vec<ProfileCommand>,
+    variables: Vec<(String, String)>,
     path: PathBuf,
 }
 
 impl Profile {
-    fn all_commands(&self) -> Vec<(String, String)> {
+    fn directory(&self) -> &Path {
+        self.path.parent().unwrap()
+    }
+
+    fn find(&self, name: &str) -> Option<ProfileCommand> {
+        self.all_commands()
+            .into_iter()
+            .find(|command| command.name == name)
+    }
+
+    fn all_commands(&self) -> Vec<ProfileCommand> {
         let mut commands = self.commands.clone();
         commands.extend(self.internal_commands.clone());
         commands
@@ -21,20 +96,33 @@ impl Profile {
 }
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    disable_help_flag = true,
+    after_help = EXIT_CODES
+)]
 struct Cli {
-    #[clap(help = "The command in the profile to run")]
+    #[clap(long, action = clap::ArgAction::Help, help = "Print help")]
+    help: Option<bool>,
+
+    #[clap(
+        help = "The command in the profile to run",
+        add = ArgValueCandidates::new(command_candidates)
+    )]
     command: Option<String>,
     #[clap(
         short,
         long,
-        help = "Prepends argument to the returned command replacing {} with the full path to the found .ok file."
+        help = "Prepends argument to the returned command replacing {} with the full path to the found .ok file and {env:NAME} with the environment variable NAME. The placeholders filled in by okeydokey, such as {cwd}, are filled in too."
     )]
     prefix: Option<String>,
     #[clap(
         short,
         long,
-        help = "Appends argument to the returned command replacing {} with the full path to the found .ok file."
+        help = "Appends argument to the returned command replacing {} with the full path to the found .ok file and {env:NAME} with the environment variable NAME. The placeholders filled in by okeydokey, such as {cwd}, are filled in too."
     )]
     suffix: Option<String>,
     #[clap(
@@ -42,207 +130,994 @@ struct Cli {
         long,
         num_args = 0..,
         allow_hyphen_values = true,
-        help = "Fills {n} in the matched command with the nth arguments in this list. If less than n arguments provided, empty string is substituted instead. If more than the total holes in the command are provided, then the arguments are appended to the command separated by spaces."
+        help = "Fills {0}, {1}, ... in the matched command with the arguments in this list, in order. Arguments of the form name=value fill {name} instead, and n=value fill {n}. Holes without an argument are filled with their default, written {name=default}. Otherwise numbered holes are left empty and named ones are left as written. Holes written {name!} are required. {env:NAME} is filled with the environment variable NAME, or with default when written {env:NAME:-default}. {profile_dir}, {profile_file}, {cwd}, {rel} (the current directory relative to the profile), {git_root} and {os} are filled in by okeydokey unless given. Filters written after a hole, as in {name|upper}, transform its value: upper, lower, slug, abs, basename, quote, join:SEPARATOR and raw, which leaves the value unquoted. If more than the total holes in the command are provided, then the arguments are appended to the command separated by spaces."
     )]
     args: Vec<String>,
 
+    #[clap(
+        long,
+        value_name = "NAME=VALUE",
+        value_parser = parse_assignment,
+        help = "Fills {name} in the matched command with value, overriding the profile's variable of that name. Can be repeated."
+    )]
+    set: Vec<(String, String)>,
+
     #[clap(
         short,
         long,
         help = "Whether to instead of running a command, print out the hook for the given platform shell."
     )]
     hook: Option<String>,
+
+    #[clap(
+        long,
+        help = "Whether to instead of running a command, print out the completion script for the given shell. One of bash, zsh, fish, pwsh or elvish."
+    )]
+    completions: Option<String>,
+
+    #[clap(
+        short,
+        long,
+        value_enum,
+        default_value_t = Format::Text,
+        help = "The format to print the command listing or the matched command in."
+    )]
+    format: Format,
+
+    #[clap(
+        short,
+        long,
+        help = "Runs the matched command in the directory of the found .ok file instead of printing it, exiting with the command's status code."
+    )]
+    run: bool,
+
+    #[clap(
+        long,
+        help = "The shell that arguments are quoted for and that --run runs commands with. When running, defaults to $OKEYDOKEY_SHELL, then $SHELL, then sh (cmd on Windows). When printing without it, arguments are not quoted."
+    )]
+    shell: Option<String>,
+
+    #[clap(flatten)]
+    matching: MatchOptions,
+
+    #[clap(
+        short,
+        long,
+        conflicts_with = "command",
+        help = "Pick the command from an interactive list, prompting for any arguments it takes."
+    )]
+    interactive: bool,
+
+    #[clap(
+        long,
+        help = "Instead of printing the command, explain which .ok file was used, how the command was picked and how its arguments were filled in."
+    )]
+    explain: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+}
+
+const EXIT_CODES: &str = indoc! {"
+    Exit codes:
+      3  No .ok file found in this directory or its parents
+      4  No command matched
+      5  More than one command matched
+      6  The .ok file could not be parsed
+      7  Invalid shell passed to --hook or --completions
+      8  The interactive picker was closed without picking a command
+      9  The interactive picker could not use the terminal
+     10  A required hole in the command was not given a value
+     11  A variable written $(command) or a placeholder such as {git_root} could not
+         be worked out
+     12  The output could not be written
+     13  The shell given to --run could not be started
+"};
+
+enum Error {
+    NoProfile,
+    NoMatch(String, Vec<String>),
+    Ambiguous(String, Vec<String>),
+    Parse(ProfileError),
+    InvalidShell(String, String),
+    Cancelled,
+    Terminal(io::Error),
+    MissingArguments(String, Vec<String>),
+    Evaluation(String, String),
+    Output(io::Error),
+    Spawn(String, io::Error),
+}
+
+impl Error {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Error::NoProfile => 3,
+            Error::NoMatch(_, _) => 4,
+            Error::Ambiguous(_, _) => 5,
+            Error::Parse(_) => 6,
+            Error::InvalidShell(_, _) => 7,
+            Error::Cancelled => 8,
+            Error::Terminal(_) => 9,
+            Error::MissingArguments(_, _) => 10,
+            Error::Evaluation(_, _) => 11,
+            Error::Output(_) => 12,
+            Error::Spawn(_, _) => 13,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NoProfile => write!(f, "No .ok file found in this directory or its parents"),
+            Error::NoMatch(command, suggestions) => {
+                write!(f, "No command matches '{}'", command)?;
+                if !suggestions.is_empty() {
+                    let suggestions = suggestions
+                        .iter()
+                        .map(|suggestion| format!("'{}'", suggestion))
+                        .collect::<Vec<_>>();
+                    write!(f, "; did you mean {}?", suggestions.join(" or "))?;
+                }
+                Ok(())
+            }
+            Error::Ambiguous(command, candidates) => write!(
+                f,
+                "'{}' is ambiguous between {}",
+                command,
+                candidates.join(", ")
+            ),
+            Error::Parse(error) => write!(f, "{}", error),
+            Error::InvalidShell(shell, options) => {
+                write!(f, "Invalid shell '{}'. Try {}", shell, options)
+            }
+            Error::Cancelled => write!(f, "No command was picked"),
+            Error::Terminal(error) => write!(f, "Could not open the picker: {}", error),
+            Error::MissingArguments(command, holes) => {
+                write!(f, "'{}' requires a value for {}", command, holes.join(", "))
+            }
+            Error::Evaluation(variable, reason) => {
+                write!(f, "Could not work out {{{}}}, {}", variable, reason)
+            }
+            Error::Output(error) => write!(f, "Could not write the output: {}", error),
+            Error::Spawn(shell, error) => write!(f, "Could not run {}: {}", shell, error),
+        }
+    }
+}
+
+fn parse_assignment(assignment: &str) -> Result<(String, String), String> {
+    match assignment.split_once('=') {
+        Some((name, value)) => Ok((name.to_string(), value.to_string())),
+        None => Err(format!("expected NAME=VALUE but found '{}'", assignment)),
+    }
 }
 
 fn main() {
+    CompleteEnv::with_factory(Cli::command).complete();
     let cli = Cli::parse();
 
+    match execute(cli) {
+        // A pipe closed by the reader, as by `okeydokey | head`, means nothing
+        // more was wanted rather than that something went wrong.
+        Err(Error::Output(error)) if error.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(error.exit_code());
+        }
+        Ok(()) => {}
+    }
+}
+
+fn execute(cli: Cli) -> Result<(), Error> {
+    if let Some(shell) = cli.completions {
+        let shells = Shells::builtins();
+        let completer = shells.completer(&shell).ok_or_else(|| {
+            Error::InvalidShell(shell.clone(), "bash, zsh, fish, pwsh or elvish".to_string())
+        })?;
+        return completer
+            .write_registration(
+                "COMPLETE",
+                "okeydokey",
+                "okeydokey",
+                "okeydokey",
+                &mut io::stdout(),
+            )
+            .map_err(Error::Output);
+    }
+
     if let Some(shell) = cli.hook {
-        let hook = match shell.as_ref() {
-            "pwsh" => Some(indoc! {r#"
-                function ok
-                {
-                    if ($args.Count -eq 0) {
-                        okeydokey | Write-Host -ForegroundColor 'Blue'
-                    } else {
-                        if ($args.Count -gt 1) {
-                            $script = okeydokey $args[0] -p "pushd {};" -s "; popd" -a ($args | select -skip 1)
-                        } else {
-                            $script = okeydokey $args[0] -p "pushd {};" -s "; popd"
-                        }
-
-                        if ($script -ne $null) {
-                            iex $script
-                        }
-                    }
-                }
-            "#}),
-            _ => None,
+        let hook = hooks::hook(&shell)
+            .ok_or_else(|| Error::InvalidShell(shell.clone(), hooks::SHELLS.join(", ")))?;
+        return writeln!(io::stdout(), "{}", hook).map_err(Error::Output);
+    }
+
+    let profile = find_profile(env::current_dir().unwrap())
+        .map_err(Error::Parse)?
+        .ok_or(Error::NoProfile)?;
+
+    let shell = match cli.run {
+        true => Some(cli.shell.unwrap_or_else(default_shell)),
+        false => cli.shell,
+    };
+    let context = Context::new(&profile, shell.clone().unwrap_or_else(default_shell));
+    let mut args = cli.args;
+    let command = if cli.interactive {
+        let (name, hole_args) = picker::pick(&profile.commands, &context)
+            .map_err(Error::Terminal)?
+            .ok_or(Error::Cancelled)?;
+        args = hole_args.into_iter().chain(args).collect();
+        Some(name)
+    } else {
+        cli.command
+    };
+
+    if let Some(command) = command {
+        let invocation = Invocation {
+            command,
+            prefix: cli.prefix,
+            suffix: cli.suffix,
+            args,
+            set: cli.set,
+            shell: shell.as_deref().map(Shell::from_name),
         };
+        if cli.explain {
+            return explain(profile, &context, invocation, &cli.matching);
+        }
 
-        if let Some(hook) = hook {
-            println!("{}", hook);
+        let profile_directory = profile.directory().to_path_buf();
+        let query = query(profile, &context, invocation, &cli.matching)?;
+        if cli.run {
+            let shell = shell.unwrap();
+            process::exit(run(&shell, &query.command, &profile_directory)?);
+        } else if cli.format == Format::Json {
+            let arguments = query
+                .filled
+                .substitutions
+                .iter()
+                .map(|(hole, value)| json!({ "hole": hole.to_string(), "value": value }))
+                .collect::<Vec<_>>();
+            let output = json!({
+                "name": query.name,
+                "command": query.command,
+                "directory": profile_directory,
+                "arguments": arguments,
+                "appended": query.filled.appended,
+            });
+            writeln!(io::stdout(), "{}", output).map_err(Error::Output)?;
         } else {
-            eprintln!("Invalid hook. Try pwsh");
+            writeln!(io::stdout(), "{}", query.command).map_err(Error::Output)?;
         }
-        return;
+    } else {
+        list(profile, cli.format).map_err(Error::Output)?;
     }
 
-    let profile_opt = find_profile(env::current_dir().unwrap());
-    if profile_opt.is_some() {
-        let profile = profile_opt.unwrap();
+    Ok(())
+}
 
-        if let Some(command) = cli.command {
-            query(profile, command, cli.prefix, cli.suffix, cli.args);
-        } else {
-            list(profile);
-        }
-    }
+fn command_candidates() -> Vec<CompletionCandidate> {
+    env::current_dir()
+        .ok()
+        .and_then(|directory| find_profile(directory).ok().flatten())
+        .map(|profile| {
+            profile
+                .commands
+                .into_iter()
+                .map(|command| {
+                    CompletionCandidate::new(command.name).help(command.description.map(Into::into))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
-fn find_profile(current_path: PathBuf) -> Option<Profile> {
+fn find_profile(current_path: PathBuf) -> Result<Option<Profile>, ProfileError> {
     let possible_profile = current_path.join(".ok");
     if possible_profile.exists() {
-        Some(read_profile(possible_profile)?)
+        read_profile(possible_profile).map(Some)
     } else {
-        Some(find_profile(current_path.parent()?.to_path_buf())?)
+        match current_path.parent() {
+            Some(parent) => find_profile(parent.to_path_buf()),
+            None => Ok(None),
+        }
     }
 }
 
-fn read_profile(profile_path: PathBuf) -> Option<Profile> {
-    match File::open(profile_path.clone()) {
-        Ok(ref mut file) => {
-            let mut commands = Vec::new();
-            let mut internal_commands = Vec::new();
+fn read_profile(profile_path: PathBuf) -> Result<Profile, ProfileError> {
+    let error = |line: usize, column: usize, text: &str, reason: String| ProfileError {
+        path: profile_path.clone(),
+        line,
+        column,
+        text: text.to_string(),
+        reason,
+    };
+
+    let file = File::open(&profile_path).map_err(|e| error(0, 0, "", e.to_string()))?;
+    let mut commands = Vec::new();
+    let mut internal_commands = Vec::new();
+    let mut variables = Vec::new();
+    let mut in_variables = false;
+    let mut description: Option<String> = None;
+
+    for (index, bytes) in BufReader::new(file).split(b'\n').enumerate() {
+        let line_number = index + 1;
+        let bytes = bytes.map_err(|e| error(line_number, 0, "", e.to_string()))?;
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(&bytes);
+        let line = std::str::from_utf8(bytes).map_err(|e| {
+            let valid = String::from_utf8_lossy(&bytes[..e.valid_up_to()]);
+            let text = String::from_utf8_lossy(bytes);
+            error(
+                line_number,
+                valid.chars().count() + 1,
+                &text,
+                "invalid UTF-8".to_string(),
+            )
+        })?;
 
-            for line in BufReader::new(file).lines() {
-                let (name, command) = split_on_colon(line.unwrap())?;
-                if name.starts_with("_") {
-                    internal_commands.push((name, command));
-                } else {
-                    commands.push((name, command));
+        if let Some(text) = line.trim_start().strip_prefix("##") {
+            let text = text.trim();
+            description = Some(match description {
+                Some(previous) => previous + " " + text,
+                None => text.to_string(),
+            });
+            continue;
+        }
+
+        let content = strip_comment(line).trim_end();
+        if content.trim_start().is_empty() {
+            description = None;
+            continue;
+        }
+
+        let indent = content.len() - content.trim_start().len();
+        let trimmed = content.trim_start();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            in_variables = match trimmed {
+                "[vars]" => true,
+                "[commands]" => false,
+                _ => {
+                    return Err(error(
+                        line_number,
+                        indent + 1,
+                        line,
+                        format!("unknown section {}, expected [vars] or [commands]", trimmed),
+                    ))
                 }
+            };
+            description = None;
+            continue;
+        }
+
+        if in_variables || trimmed.starts_with('$') {
+            let assignment = trimmed.strip_prefix('$').unwrap_or(trimmed);
+            let (name, value) = assignment.split_once('=').ok_or_else(|| {
+                error(
+                    line_number,
+                    indent + 1,
+                    line,
+                    "expected a variable in the form `name = value` but found no equals sign"
+                        .to_string(),
+                )
+            })?;
+            let name = name.trim();
+            if !template::is_name(name) {
+                return Err(error(
+                    line_number,
+                    indent + 1,
+                    line,
+                    format!("`{}` is not a valid variable name", name),
+                ));
             }
+            variables.push((name.to_string(), value.trim().to_string()));
+            description = None;
+            continue;
+        }
 
-            Some(Profile {
-                internal_commands,
-                commands,
-                path: profile_path,
-            })
+        let (name, command) = split_on_colon(content.to_string()).ok_or_else(|| {
+            error(
+                line_number,
+                indent + 1,
+                line,
+                "expected a command in the form `name: command` but found no colon".to_string(),
+            )
+        })?;
+        if name.trim().is_empty() {
+            return Err(error(
+                line_number,
+                name.len() + 1,
+                line,
+                "command name is empty".to_string(),
+            ));
+        }
+
+        let command = ProfileCommand {
+            name,
+            command,
+            description: description.take(),
+            line: line_number,
+        };
+        if command.name.starts_with("_") {
+            internal_commands.push(command);
+        } else {
+            commands.push(command);
         }
-        Err(_) => None,
     }
+
+    Ok(Profile {
+        internal_commands,
+        commands,
+        variables,
+        path: profile_path,
+    })
+}
+
+// A # only starts a comment where the shell would treat it as one: at the start
+// of a word and outside of quotes. Anything else is left as part of the command.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = None;
+    for (index, character) in line.char_indices() {
+        match (quote, character) {
+            (Some(open), _) if character == open => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(character),
+            (None, '#') if previous.is_none_or(char::is_whitespace) => return &line[..index],
+            _ => {}
+        }
+        previous = Some(character);
+    }
+    line
 }
 
 fn split_on_colon(line: String) -> Option<(String, String)> {
-    let mut splitter = line.splitn(2, ':');
-    let name = splitter.next()?;
-    let command = splitter.next()?;
-    Some((name.to_string(), command.to_string()))
+    let (name, command) = line.split_once(':')?;
+    Some((name.to_string(), command.trim().to_string()))
 }
 
-fn list(profile: Profile) {
-    let list = profile
+fn list(profile: Profile, format: Format) -> io::Result<()> {
+    let mut out = io::stdout();
+    if format == Format::Json {
+        let list = profile
+            .all_commands()
+            .iter()
+            .map(|command| {
+                json!({
+                    "name": command.name,
+                    "body": command.command,
+                    "description": command.description,
+                    "internal": command.name.starts_with('_'),
+                    "file": profile.path,
+                    "line": command.line,
+                })
+            })
+            .collect::<Vec<_>>();
+        return writeln!(out, "{}", json!(list));
+    }
+
+    let width = profile
         .commands
         .iter()
-        .map(|(name, _)| name)
-        .fold(String::new(), |acc, next| acc + " " + next);
-    println!("{}", list.trim());
+        .map(|command| command.name.chars().count())
+        .max()
+        .unwrap_or_default();
+    for command in profile.commands {
+        let description = command.description.unwrap_or_default();
+        let line = format!("{:width$}  {}", command.name, description);
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
 }
 
-fn query(
-    profile: Profile,
+enum Reason {
+    Exact,
+    Only,
+    HighestScore,
+    Shortest,
+    FirstListed,
+    Autocorrected(String),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::Exact => write!(f, "the name matches exactly"),
+            Reason::Only => write!(f, "it is the only match"),
+            Reason::HighestScore => write!(f, "it has the highest score"),
+            Reason::Shortest => write!(f, "it is the shortest of the highest scoring matches"),
+            Reason::FirstListed => write!(
+                f,
+                "it is listed first of the shortest highest scoring matches"
+            ),
+            Reason::Autocorrected(command) => write!(
+                f,
+                "nothing matched '{}' and it is the only close suggestion",
+                command
+            ),
+        }
+    }
+}
+
+// What was typed on the command line to pick and fill in a command.
+struct Invocation {
     command: String,
     prefix: Option<String>,
     suffix: Option<String>,
     args: Vec<String>,
-) -> Option<()> {
+    set: Vec<(String, String)>,
+    shell: Option<Shell>,
+}
+
+struct Query {
+    name: String,
+    filled: Filled,
+    command: String,
+}
+
+struct Resolution {
+    name: String,
+    candidates: Vec<(usize, String)>,
+    reason: Reason,
+}
+
+fn query(
+    profile: Profile,
+    context: &Context,
+    invocation: Invocation,
+    options: &MatchOptions,
+) -> Result<Query, Error> {
+    let command = invocation.command;
+    let resolution = resolve(&profile, &command, options)?;
+    match resolution.reason {
+        Reason::Autocorrected(_) => eprintln!(
+            "No command matches '{}', running '{}' instead",
+            command, resolution.name
+        ),
+        Reason::HighestScore | Reason::Shortest | Reason::FirstListed => {
+            let passed_over = resolution
+                .candidates
+                .iter()
+                .map(|(_, name)| name.clone())
+                .filter(|name| *name != resolution.name)
+                .collect::<Vec<_>>();
+            eprintln!(
+                "'{}' matched {}, passing over {}",
+                command,
+                resolution.name,
+                passed_over.join(", ")
+            );
+        }
+        Reason::Exact | Reason::Only => {}
+    }
+
+    let template = profile.find(&resolution.name).unwrap().command;
+    let filled = fill_in_arguments(
+        &template,
+        invocation.args,
+        &invocation.set,
+        context,
+        invocation.shell,
+    );
+    let command = decorate_command(
+        context,
+        &filled.command,
+        invocation.prefix,
+        invocation.suffix,
+        &invocation.set,
+        invocation.shell,
+    );
+    if let Some((variable, reason)) = context.failure() {
+        return Err(Error::Evaluation(variable, reason));
+    }
+    if !filled.missing.is_empty() {
+        let missing = filled.missing.iter().map(|hole| hole.to_string()).collect();
+        return Err(Error::MissingArguments(resolution.name, missing));
+    }
+    for hole in &filled.unfilled {
+        eprintln!("No value given for {}, leaving it as written", hole);
+    }
+    Ok(Query {
+        name: resolution.name,
+        filled,
+        command,
+    })
+}
+
+fn explain(
+    profile: Profile,
+    context: &Context,
+    invocation: Invocation,
+    options: &MatchOptions,
+) -> Result<(), Error> {
+    let mut out = io::stdout();
+    writeln!(out, "Profile:    {}", profile.path.display()).map_err(Error::Output)?;
+    writeln!(
+        out,
+        "Matcher:    {}",
+        options.matcher.to_possible_value().unwrap().get_name()
+    )
+    .map_err(Error::Output)?;
+    let resolution = resolve(&profile, &invocation.command, options)?;
+    let candidates = resolution
+        .candidates
+        .iter()
+        .map(|(score, name)| format!("{} ({})", name, score))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        writeln!(out, "Candidates: none").map_err(Error::Output)?;
+    } else {
+        writeln!(out, "Candidates: {}", candidates.join(", ")).map_err(Error::Output)?;
+    }
+    writeln!(
+        out,
+        "Chosen:     {}, because {}",
+        resolution.name, resolution.reason
+    )
+    .map_err(Error::Output)?;
+
+    let template = profile.find(&resolution.name).unwrap().command;
+    let filled = fill_in_arguments(
+        &template,
+        invocation.args,
+        &invocation.set,
+        context,
+        invocation.shell,
+    );
+    match invocation.shell {
+        Some(shell) => writeln!(out, "Quoting:    {:?}", shell),
+        None => writeln!(out, "Quoting:    none"),
+    }
+    .map_err(Error::Output)?;
+    writeln!(out, "Template:   {}", template).map_err(Error::Output)?;
+    for (hole, value) in &filled.substitutions {
+        if filled.missing.contains(hole) {
+            writeln!(out, "  {} <- nothing (required, no value given)", hole)
+                .map_err(Error::Output)?;
+        } else if filled.unfilled.contains(hole) {
+            writeln!(
+                out,
+                "  {} <- nothing (no value given, left as written)",
+                hole
+            )
+            .map_err(Error::Output)?;
+        } else if filled.defaulted.contains(hole) {
+            match hole {
+                Hole::Name(name) if context.variable(name).is_some() => {
+                    writeln!(out, "  {} <- {:?} (profile variable)", hole, value)
+                }
+                _ => writeln!(out, "  {} <- {:?} (default)", hole, value),
+            }
+            .map_err(Error::Output)?;
+        } else {
+            writeln!(out, "  {} <- {:?}", hole, value).map_err(Error::Output)?;
+        }
+    }
+    for arg in &filled.appended {
+        writeln!(out, "  appended {:?}", arg).map_err(Error::Output)?;
+    }
+    writeln!(out, "Filled:     {}", filled.command.trim()).map_err(Error::Output)?;
+    writeln!(
+        out,
+        "Command:    {}",
+        decorate_command(
+            context,
+            &filled.command,
+            invocation.prefix,
+            invocation.suffix,
+            &invocation.set,
+            invocation.shell
+        )
+    )
+    .map_err(Error::Output)?;
+    match context.failure() {
+        Some((variable, reason)) => Err(Error::Evaluation(variable, reason)),
+        None => Ok(()),
+    }
+}
+
+fn resolve(profile: &Profile, command: &str, options: &MatchOptions) -> Result<Resolution, Error> {
+    match best_match(profile, command, options) {
+        Err(Error::NoMatch(command, suggestions))
+            if options.autocorrect && suggestions.len() == 1 =>
+        {
+            Ok(Resolution {
+                name: suggestions[0].clone(),
+                candidates: Vec::new(),
+                reason: Reason::Autocorrected(command),
+            })
+        }
+        result => result,
+    }
+}
+
+// A name listed more than once is only counted once. Unless --strict is given,
+// a tie that even the shortest name can't break goes to the one listed first.
+fn best_match(
+    profile: &Profile,
+    command: &str,
+    options: &MatchOptions,
+) -> Result<Resolution, Error> {
     let commands_with_valid_prefix_count = profile
         .all_commands()
         .iter()
-        .filter_map(|(possible_command, _)| shared_prefix(possible_command, &command))
-        .collect::<Vec<_>>();
+        .filter_map(|possible_command| {
+            let score = options.matcher.score(&possible_command.name, command)?;
+            Some((score, possible_command.name.clone()))
+        })
+        .fold(
+            Vec::new(),
+            |mut matches: Vec<(usize, String)>, (score, name)| {
+                if !matches.iter().any(|(_, existing)| *existing == name) {
+                    matches.push((score, name));
+                }
+                matches
+            },
+        );
+
+    if profile.find(command).is_some() {
+        return Ok(Resolution {
+            name: command.to_string(),
+            candidates: commands_with_valid_prefix_count,
+            reason: Reason::Exact,
+        });
+    }
 
     let most_shared_chars = commands_with_valid_prefix_count
         .iter()
-        .cloned()
-        .map(|(shared_chars, _)| shared_chars)
-        .max()?;
+        .map(|(shared_chars, _)| *shared_chars)
+        .max()
+        .ok_or_else(|| Error::NoMatch(command.to_string(), suggestions(profile, command)))?;
 
-    let best_command = commands_with_valid_prefix_count
-        .into_iter()
+    let candidates = commands_with_valid_prefix_count
+        .iter()
+        .map(|(_, command)| command.clone())
+        .collect::<Vec<_>>();
+    if options.strict && candidates.len() > 1 {
+        return Err(Error::Ambiguous(command.to_string(), candidates));
+    }
+
+    let best_commands = commands_with_valid_prefix_count
+        .iter()
         .filter(|(shared_chars, _)| *shared_chars == most_shared_chars)
-        .map(|(_, command)| command)
-        .min_by_key(|command| command.len())?;
+        .map(|(_, command)| command.clone())
+        .collect::<Vec<_>>();
+    let reason = match (candidates.len(), best_commands.len()) {
+        (1, _) => Reason::Only,
+        (_, 1) => Reason::HighestScore,
+        _ => Reason::Shortest,
+    };
+    let shortest = best_commands
+        .iter()
+        .map(|command| command.len())
+        .min()
+        .unwrap_or_default();
+    let mut best_commands = best_commands
+        .into_iter()
+        .filter(|command| command.len() == shortest)
+        .collect::<Vec<_>>();
+    let reason = match best_commands.len() {
+        1 => reason,
+        _ => Reason::FirstListed,
+    };
 
-    print_decorated_command(profile, best_command, prefix, suffix, args);
-    Some(())
+    Ok(Resolution {
+        name: best_commands.remove(0),
+        candidates: commands_with_valid_prefix_count,
+        reason,
+    })
 }
 
-fn shared_prefix(possible_command: &str, command: &str) -> Option<(usize, String)> {
-    match possible_command.starts_with(command) {
-        true => Some((command.len(), possible_command.to_string())),
-        false => None,
-    }
+// Typos are compared against both the whole name and the start of it so that
+// misspelled prefixes such as "biu" still suggest "build".
+fn suggestions(profile: &Profile, command: &str) -> Vec<String> {
+    let allowed_distance = (command.chars().count() / 3).max(1);
+    let mut suggestions = profile
+        .all_commands()
+        .into_iter()
+        .filter_map(|possible_command| {
+            let name = possible_command.name;
+            let start = name
+                .chars()
+                .take(command.chars().count())
+                .collect::<String>();
+            let distance = strsim::damerau_levenshtein(command, &name)
+                .min(strsim::damerau_levenshtein(command, &start));
+            (distance <= allowed_distance).then_some((distance, name))
+        })
+        .collect::<Vec<_>>();
+    suggestions.sort_by_key(|(distance, name)| (*distance, name.len()));
+    suggestions
+        .into_iter()
+        .take(3)
+        .map(|(_, name)| name)
+        .collect()
 }
 
-fn print_decorated_command(
-    profile: Profile,
-    command_name: String,
+fn decorate_command(
+    context: &Context,
+    command: &str,
     prefix: Option<String>,
     suffix: Option<String>,
-    args: Vec<String>,
-) {
-    let prefix = fill_in_profile_directory(&profile, prefix);
-    let suffix = fill_in_profile_directory(&profile, suffix);
-    let (_, command) = profile
-        .all_commands()
-        .into_iter()
-        .find(|(name, _)| *name == command_name)
-        .unwrap();
+    set: &[(String, String)],
+    shell: Option<Shell>,
+) -> String {
+    let mut prefix = fill_in_context(context, prefix, set, shell);
+    let suffix = fill_in_context(context, suffix, set, shell);
+    // Bodies are trimmed, so a prefix such as `time` needs the space that used
+    // to come from the body.
+    if !prefix.is_empty() && !prefix.ends_with(char::is_whitespace) {
+        prefix.push(' ');
+    }
 
-    println!(
-        "{}",
-        vec![prefix, fill_in_arguments(command.to_string(), args), suffix].concat()
-    )
+    [prefix.as_str(), command, suffix.as_str()].concat()
 }
 
-fn fill_in_profile_directory(profile: &Profile, pattern: Option<String>) -> String {
-    let profile_directory = profile.path.parent().unwrap().to_str().unwrap();
-    pattern.unwrap_or_default().replace("{}", profile_directory)
+// {} is the profile directory, as {profile_dir} is, but is never quoted so that
+// existing prefixes which quote it themselves keep working.
+fn fill_in_context(
+    context: &Context,
+    pattern: Option<String>,
+    set: &[(String, String)],
+    shell: Option<Shell>,
+) -> String {
+    let profile_directory = context.profile_directory().to_str().unwrap();
+    let pattern = pattern.unwrap_or_default().replace("{}", profile_directory);
+    fill_in_variables(&pattern, set, context, shell)
 }
 
-fn hole(n: usize) -> String {
-    format!("{{{}}}", n)
+fn default_shell() -> String {
+    env::var("OKEYDOKEY_SHELL")
+        .or_else(|_| env::var("SHELL"))
+        .unwrap_or_else(|_| {
+            if cfg!(windows) {
+                "cmd".to_string()
+            } else {
+                "sh".to_string()
+            }
+        })
 }
 
-fn count_holes(command: &String) -> usize {
-    fn rec(command: &String, n: usize) -> usize {
-        match command.contains(&hole(n)[..]) {
-            true => rec(command, n + 1),
-            false => n,
+// Exits as a shell would, with 128 plus the signal when the command was killed
+// by one.
+fn run(shell: &str, command: &str, directory: &Path) -> Result<i32, Error> {
+    let status = Command::new(shell)
+        .arg(Shell::from_name(shell).command_flag())
+        .arg(command)
+        .current_dir(directory)
+        .status()
+        .map_err(|error| Error::Spawn(shell.to_string(), error))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return Ok(128 + signal);
         }
     }
-
-    rec(command, 0)
+    Ok(status.code().unwrap_or(1))
 }
 
-fn fill_in_arguments(perferated_command: String, args: Vec<String>) -> String {
-    let number_of_holes = count_holes(&perferated_command);
-    let mut args_iterator = args.iter();
-    let mut command = perferated_command;
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-    for hole_number in 0..number_of_holes {
-        let hole_string = hole(hole_number);
-        command = match args_iterator.next() {
-            Some(arg) => command.replace(&hole_string[..], arg),
-            None => command.replace(&hole_string[..], ""),
-        };
+    use super::*;
+
+    // Each profile gets its own file since tests run in parallel.
+    fn read(contents: &[u8]) -> Result<Profile, ProfileError> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "okeydokey-{}-{}.ok",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, contents).unwrap();
+        let profile = read_profile(path.clone());
+        fs::remove_file(path).unwrap();
+        profile
+    }
+
+    fn commands(profile: &Profile) -> Vec<(&str, &str)> {
+        profile
+            .commands
+            .iter()
+            .map(|command| (command.name.as_str(), command.command.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn comments_start_at_a_word_outside_quotes() {
+        assert_eq!(
+            strip_comment("echo '#not' \"#not\""),
+            "echo '#not' \"#not\""
+        );
+        assert_eq!(strip_comment("echo foo#bar"), "echo foo#bar");
+        assert_eq!(strip_comment("echo hi # says hi"), "echo hi ");
+        assert_eq!(strip_comment("# whole line"), "");
+    }
+
+    #[test]
+    fn splits_on_the_first_colon() {
+        assert_eq!(
+            split_on_colon("open: xdg-open http://localhost".to_string()),
+            Some(("open".to_string(), "xdg-open http://localhost".to_string()))
+        );
+        assert_eq!(split_on_colon("no colon".to_string()), None);
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let profile = read(b"# note\n\nbuild: cargo build # debug\n   \ntest: cargo test\n")
+            .ok()
+            .unwrap();
+        assert_eq!(
+            commands(&profile),
+            vec![("build", "cargo build"), ("test", "cargo test")]
+        );
+        assert_eq!(profile.commands[1].line, 5);
     }
 
-    for arg in args_iterator {
-        command = command + " " + arg;
+    #[test]
+    fn descriptions_are_reset_by_blank_lines() {
+        let profile =
+            read(b"## Stale\n\n## Builds\n## everything\nbuild: cargo build\ntest: cargo test\n")
+                .ok()
+                .unwrap();
+        assert_eq!(
+            profile.commands[0].description.as_deref(),
+            Some("Builds everything")
+        );
+        assert_eq!(profile.commands[1].description, None);
     }
 
-    command
+    fn error(contents: &[u8]) -> (usize, usize, String) {
+        match read(contents) {
+            Ok(_) => panic!("expected the profile not to parse"),
+            Err(error) => (error.line, error.column, error.reason),
+        }
+    }
+
+    #[test]
+    fn reports_a_missing_colon_at_the_command() {
+        let (line, column, reason) = error(b"build: cargo build\n  oops\n");
+        assert_eq!((line, column), (2, 3));
+        assert!(reason.contains("no colon"));
+    }
+
+    #[test]
+    fn reports_an_empty_name() {
+        let (line, column, reason) = error(b"   : echo\n");
+        assert_eq!((line, column), (1, 4));
+        assert_eq!(reason, "command name is empty");
+    }
+
+    #[test]
+    fn reports_invalid_utf8_at_the_character() {
+        let (line, column, reason) = error(b"ok: echo \xc3\xa9\nbad: echo \xe9\xff\n");
+        assert_eq!((line, column), (2, 11));
+        assert_eq!(reason, "invalid UTF-8");
+
+        let (_, column, _) = error(b"\xc3\xa9\xc3\xa9: \xff\n");
+        assert_eq!(column, 5);
+    }
+
+    #[test]
+    fn accepts_crlf_line_endings() {
+        let profile = read(b"## Builds\r\nbuild: cargo build\r\n\r\ntest: cargo test\r\n")
+            .ok()
+            .unwrap();
+        assert_eq!(
+            commands(&profile),
+            vec![("build", "cargo build"), ("test", "cargo test")]
+        );
+        assert_eq!(profile.commands[0].description.as_deref(), Some("Builds"));
+    }
+
+    #[test]
+    fn reports_an_unknown_section() {
+        let (line, column, reason) = error(b"build: cargo build\n[stuff]\n");
+        assert_eq!((line, column), (2, 1));
+        assert!(reason.contains("unknown section [stuff]"));
+    }
 }
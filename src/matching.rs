@@ -0,0 +1,215 @@
+use clap::builder::FalseyValueParser;
+use clap::{Args, ValueEnum};
+
+#[derive(Args, Debug)]
+pub struct MatchOptions {
+    #[clap(
+        short,
+        long,
+        value_enum,
+        env = "OKEYDOKEY_MATCHER",
+        default_value_t = Matcher::Prefix,
+        help = "How the typed command is matched against the names in the profile."
+    )]
+    pub matcher: Matcher,
+
+    #[clap(
+        long,
+        env = "OKEYDOKEY_AUTOCORRECT",
+        value_parser = FalseyValueParser::new(),
+        help = "When no command matches and there is exactly one close suggestion, use it instead of failing."
+    )]
+    pub autocorrect: bool,
+
+    #[clap(
+        long,
+        env = "OKEYDOKEY_STRICT",
+        value_parser = FalseyValueParser::new(),
+        help = "Refuse to pick a command when what was typed matches more than one name, rather than picking the closest."
+    )]
+    pub strict: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Matcher {
+    /// Names that start with exactly what was typed.
+    Prefix,
+    /// Each typed chunk starts a dash, underscore or camel case segment, so rb finds release-build.
+    Segment,
+    /// Typed characters appear in order anywhere in the name, preferring segment starts.
+    Fuzzy,
+}
+
+impl Matcher {
+    // How well the typed command matches a name, or None if it doesn't match at
+    // all. Higher is better.
+    pub fn score(self, name: &str, command: &str) -> Option<usize> {
+        match self {
+            Matcher::Prefix => shared_prefix(name, command),
+            Matcher::Segment => segment_score(&segments(name), &lowercase(command)),
+            Matcher::Fuzzy => fuzzy_score(name, command),
+        }
+    }
+}
+
+fn shared_prefix(possible_command: &str, command: &str) -> Option<usize> {
+    match possible_command.starts_with(command) {
+        true => Some(command.len()),
+        false => None,
+    }
+}
+
+fn lowercase(text: &str) -> Vec<char> {
+    text.chars()
+        .map(|character| character.to_lowercase().next().unwrap_or(character))
+        .collect()
+}
+
+fn segment_starts(name: &[char]) -> Vec<bool> {
+    (0..name.len())
+        .map(|index| match index {
+            0 => true,
+            _ => {
+                let previous = name[index - 1];
+                let current = name[index];
+                (!previous.is_alphanumeric() && current.is_alphanumeric())
+                    || (previous.is_lowercase() && current.is_uppercase())
+            }
+        })
+        .collect()
+}
+
+fn segments(name: &str) -> Vec<Vec<char>> {
+    let characters = name.chars().collect::<Vec<_>>();
+    let mut segments: Vec<Vec<char>> = Vec::new();
+    for (character, starts) in characters.iter().zip(segment_starts(&characters)) {
+        if !character.is_alphanumeric() {
+            continue;
+        }
+        match segments.last_mut() {
+            Some(segment) if !starts => segment.extend(lowercase(&character.to_string())),
+            _ => segments.push(lowercase(&character.to_string())),
+        }
+    }
+    segments
+}
+
+// Every typed chunk has to be a prefix of a segment, in order, though segments
+// may be skipped. Splitting what was typed into fewer chunks scores higher.
+fn segment_score(segments: &[Vec<char>], command: &[char]) -> Option<usize> {
+    let chunks = segment_chunks(segments, command)?;
+    Some(2 * command.len() - chunks)
+}
+
+fn segment_chunks(segments: &[Vec<char>], command: &[char]) -> Option<usize> {
+    if command.is_empty() {
+        return Some(0);
+    }
+
+    let (segment, rest) = segments.split_first()?;
+    let shared = segment
+        .iter()
+        .zip(command)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    (1..=shared)
+        .filter_map(|taken| Some(segment_chunks(rest, &command[taken..])? + 1))
+        .chain(segment_chunks(rest, command))
+        .min()
+}
+
+// Best in order alignment of the typed characters in the name. Matches at the
+// start of a segment and runs of consecutive matches score extra.
+fn fuzzy_score(name: &str, command: &str) -> Option<usize> {
+    let characters = name.chars().collect::<Vec<_>>();
+    let starts = segment_starts(&characters);
+    let name = lowercase(name);
+    let command = lowercase(command);
+    if command.is_empty() {
+        return None;
+    }
+
+    let mut previous: Vec<Option<usize>> = Vec::new();
+    for (position, typed) in command.iter().enumerate() {
+        let current = (0..name.len())
+            .map(|index| {
+                if name[index] != *typed {
+                    return None;
+                }
+
+                let bonus = if starts[index] { 3 } else { 1 };
+                if position == 0 {
+                    return Some(bonus);
+                }
+
+                (0..index)
+                    .filter_map(|before| {
+                        let consecutive = match follows(&characters, &starts, before, index) {
+                            true => 2,
+                            false => 0,
+                        };
+                        Some(previous[before]? + consecutive)
+                    })
+                    .max()
+                    .map(|score| score + bonus)
+            })
+            .collect();
+        previous = current;
+    }
+
+    previous.into_iter().flatten().max()
+}
+
+// Whether a match at index continues a run from the match at before. Moving on
+// to the start of the next segment counts, whether that is across a separator
+// as in db-migrate or a camel hump as in dbMigrate.
+fn follows(characters: &[char], starts: &[bool], before: usize, index: usize) -> bool {
+    before + 1 == index
+        || (starts[index]
+            && characters[before + 1..index]
+                .iter()
+                .all(|character| !character.is_alphanumeric()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Matcher;
+
+    #[test]
+    fn segment_matches_segment_starts() {
+        assert!(Matcher::Segment.score("release-build", "rb").is_some());
+        assert!(Matcher::Segment.score("db-migrate", "dbm").is_some());
+        assert!(Matcher::Segment.score("rebuild", "rb").is_none());
+        assert!(
+            Matcher::Segment.score("release-build", "rebu")
+                > Matcher::Segment.score("release-build", "rb")
+        );
+    }
+
+    #[test]
+    fn segment_prefers_fewer_chunks() {
+        assert!(
+            Matcher::Segment.score("db-migrate", "dbm")
+                > Matcher::Segment.score("data-base-migrate", "dbm")
+        );
+    }
+
+    #[test]
+    fn fuzzy_follows_runs_across_separators() {
+        let dashed = Matcher::Fuzzy.score("db-migrate", "dbm");
+        let camel = Matcher::Fuzzy.score("dbMigrateAll", "dbm");
+        assert!(dashed.is_some());
+        assert!(dashed >= camel);
+        assert!(dashed > Matcher::Fuzzy.score("debug-mode", "dbm"));
+        assert!(
+            Matcher::Fuzzy.score("release-build", "rb") > Matcher::Fuzzy.score("rebuild", "rb")
+        );
+    }
+
+    #[test]
+    fn fuzzy_needs_every_character_in_order() {
+        assert!(Matcher::Fuzzy.score("db-migrate", "mdb").is_none());
+        assert!(Matcher::Fuzzy.score("db-migrate", "").is_none());
+    }
+}
@@ -0,0 +1,244 @@
+use std::io::{self, Write};
+
+use crossterm::cursor;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Print, PrintStyledContent, Stylize};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{execute, queue};
+
+use crate::context::Context;
+use crate::matching::Matcher;
+use crate::template::{self, Hole, Placeholder};
+use crate::ProfileCommand;
+
+const PREVIEW_ROWS: u16 = 3;
+
+// Draws on stderr so that stdout is left for the selected command, which lets
+// the picker be used from inside the shell hooks.
+pub fn pick(
+    commands: &[ProfileCommand],
+    context: &Context,
+) -> io::Result<Option<(String, Vec<String>)>> {
+    let mut stderr = io::stderr();
+    terminal::enable_raw_mode()?;
+    let selected = execute!(stderr, terminal::EnterAlternateScreen, cursor::Hide)
+        .and_then(|_| select(commands, context, &mut stderr));
+    let restored = execute!(stderr, cursor::Show, terminal::LeaveAlternateScreen);
+    terminal::disable_raw_mode()?;
+    restored?;
+    context.forget_failures();
+
+    match selected? {
+        Some(command) => {
+            let args = prompt_for_holes(command, context)?;
+            Ok(Some((command.name.clone(), args)))
+        }
+        None => Ok(None),
+    }
+}
+
+fn filter<'a>(commands: &'a [ProfileCommand], query: &str) -> Vec<&'a ProfileCommand> {
+    if query.is_empty() {
+        return commands.iter().collect();
+    }
+
+    let mut matches = commands
+        .iter()
+        .filter_map(|command| Some((Matcher::Fuzzy.score(&command.name, query)?, command)))
+        .collect::<Vec<_>>();
+    matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+    matches.into_iter().map(|(_, command)| command).collect()
+}
+
+fn select<'a>(
+    commands: &'a [ProfileCommand],
+    context: &Context,
+    out: &mut impl Write,
+) -> io::Result<Option<&'a ProfileCommand>> {
+    let mut query = String::new();
+    let mut selected = 0;
+
+    loop {
+        let matches = filter(commands, &query);
+        selected = selected.min(matches.len().saturating_sub(1));
+        draw(out, &query, &matches, selected, context)?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if control => return Ok(None),
+            KeyCode::Enter => {
+                if let Some(command) = matches.get(selected) {
+                    return Ok(Some(command));
+                }
+            }
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Char('p') if control => selected = selected.saturating_sub(1),
+            KeyCode::Down => selected += 1,
+            KeyCode::Char('n') if control => selected += 1,
+            KeyCode::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            KeyCode::Char(character) if !control => {
+                query.push(character);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(
+    out: &mut impl Write,
+    query: &str,
+    matches: &[&ProfileCommand],
+    selected: usize,
+    context: &Context,
+) -> io::Result<()> {
+    let (columns, rows) = terminal::size()?;
+    let fit = |text: String| text.chars().take(columns as usize).collect::<String>();
+
+    queue!(
+        out,
+        terminal::Clear(ClearType::All),
+        cursor::MoveTo(0, 0),
+        Print(fit(format!("> {}", query)))
+    )?;
+
+    let list_rows = rows.saturating_sub(PREVIEW_ROWS + 1).max(1) as usize;
+    let first = (selected + 1).saturating_sub(list_rows);
+    let width = matches
+        .iter()
+        .map(|command| command.name.chars().count())
+        .max()
+        .unwrap_or_default();
+    for (index, command) in matches.iter().enumerate().skip(first).take(list_rows) {
+        let description = command.description.clone().unwrap_or_default();
+        let line = fit(format!("{:width$}  {}", command.name, description));
+        queue!(out, cursor::MoveTo(0, (index - first + 1) as u16))?;
+        if index == selected {
+            queue!(out, PrintStyledContent(line.reverse()))?;
+        } else {
+            queue!(out, Print(line))?;
+        }
+    }
+
+    // Shows the command as it would run, apart from the holes that are still to
+    // be asked about.
+    if let Some(command) = matches.get(selected) {
+        let preview = template::fill_in_variables(&command.command, &[], context, None);
+        let preview_row = rows.saturating_sub(PREVIEW_ROWS);
+        queue!(
+            out,
+            cursor::MoveTo(0, preview_row),
+            PrintStyledContent("─".repeat(columns as usize).dark_grey()),
+            cursor::MoveTo(0, preview_row + 1),
+            Print(fit(preview))
+        )?;
+    }
+
+    out.flush()
+}
+
+// Holes that are filled in from the context aren't asked about.
+fn prompt_for_holes(command: &ProfileCommand, context: &Context) -> io::Result<Vec<String>> {
+    let holes = template::holes(&command.command);
+    let rest = template::rest_index(&holes);
+    let mut args = Vec::new();
+    for placeholder in &holes {
+        match &placeholder.hole {
+            Hole::Env(_) => continue,
+            Hole::Name(name) if context.defines(name) => continue,
+            _ => {}
+        }
+        match &placeholder.default {
+            Some(default) => eprint!("{} {} [{}]: ", command.name, placeholder.hole, default),
+            None => eprint!("{} {}: ", command.name, placeholder.hole),
+        }
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        let answer = answer.trim_end_matches(['\r', '\n']);
+        args.extend(answer_arguments(placeholder, answer, rest));
+    }
+    Ok(args)
+}
+
+// Answers are given by position, as 2=value, so that numbered holes the command
+// skips over aren't filled with anything. An empty answer leaves the hole to its
+// default, or to be reported as missing when it is required.
+fn answer_arguments(placeholder: &Placeholder, answer: &str, rest: usize) -> Vec<String> {
+    let indexed = |start: usize, count: usize| {
+        answer
+            .split_whitespace()
+            .take(count)
+            .enumerate()
+            .map(|(offset, word)| format!("{}={}", start + offset, word))
+            .collect()
+    };
+    match &placeholder.hole {
+        Hole::Name(name) if answer.is_empty() => {
+            match placeholder.default.is_none() && !placeholder.required {
+                true => vec![format!("{}=", name)],
+                false => Vec::new(),
+            }
+        }
+        _ if answer.is_empty() => Vec::new(),
+        Hole::Index(index) => vec![format!("{}={}", index, answer)],
+        Hole::Range(start, end) => indexed(*start, end.map_or(usize::MAX, |end| end + 1 - start)),
+        Hole::Rest | Hole::Joined => indexed(rest, usize::MAX),
+        Hole::Name(name) => vec![format!("{}={}", name, answer)],
+        Hole::Env(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::Shell;
+    use crate::template::{fill_in_arguments, Variable, Variables};
+
+    struct NoVariables;
+
+    impl Variables for NoVariables {
+        fn get(&self, _: &str) -> Option<Variable> {
+            None
+        }
+    }
+
+    fn pick(template: &str, answers: &[&str]) -> String {
+        let holes = template::holes(template);
+        let rest = template::rest_index(&holes);
+        let args = holes
+            .iter()
+            .zip(answers)
+            .flat_map(|(placeholder, answer)| answer_arguments(placeholder, answer, rest))
+            .collect();
+        fill_in_arguments(template, args, &[], &NoVariables, Some(Shell::Posix)).command
+    }
+
+    #[test]
+    fn skipped_numbers_are_not_filled() {
+        assert_eq!(pick("echo {0} {2}", &["a", "c"]), "echo a c");
+        assert_eq!(pick("echo {1..2} {@}", &["a b", "c d"]), "echo a b c d");
+        assert_eq!(pick("echo {0} {2} {@}", &["a", "", "x y"]), "echo a  x y");
+    }
+
+    #[test]
+    fn empty_answers_fall_back() {
+        assert_eq!(pick("echo {0=d} {name}", &["", ""]), "echo d ''");
+        assert_eq!(pick("echo {name=d}", &[""]), "echo d");
+
+        let args = answer_arguments(&template::holes("{0!}")[0], "", 0);
+        assert!(args.is_empty());
+        let args = answer_arguments(&template::holes("{name!}")[0], "", 0);
+        assert!(args.is_empty());
+    }
+}
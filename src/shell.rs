@@ -0,0 +1,168 @@
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Posix,
+    Fish,
+    Pwsh,
+    Cmd,
+    Nu,
+}
+
+impl Shell {
+    // Accepts either a bare name or a path such as $SHELL. Shells that aren't
+    // recognized are assumed to be POSIX compatible.
+    pub fn from_name(shell: &str) -> Shell {
+        let name = Path::new(shell)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(shell)
+            .to_lowercase();
+        match name.as_ref() {
+            "fish" => Shell::Fish,
+            "pwsh" | "powershell" => Shell::Pwsh,
+            "cmd" => Shell::Cmd,
+            "nu" => Shell::Nu,
+            _ => Shell::Posix,
+        }
+    }
+
+    pub fn command_flag(self) -> &'static str {
+        match self {
+            Shell::Pwsh => "-Command",
+            Shell::Cmd => "/C",
+            _ => "-c",
+        }
+    }
+
+    // Quotes an argument so that the shell passes it through as a single word.
+    // Arguments made only of characters that are never special are left as is.
+    pub fn quote(self, arg: &str) -> String {
+        let is_plain = !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_alphanumeric() || "_-+./:".contains(c));
+        if is_plain {
+            return arg.to_string();
+        }
+
+        match self {
+            Shell::Posix => format!("'{}'", arg.replace('\'', r"'\''")),
+            Shell::Fish => format!("'{}'", arg.replace('\\', r"\\").replace('\'', r"\'")),
+            Shell::Pwsh => format!("'{}'", quote_pwsh(arg)),
+            Shell::Cmd => format!("\"{}\"", quote_cmd(arg)),
+            Shell::Nu if arg.contains('\'') => {
+                let hashes = "#".repeat(longest_hash_run(arg) + 1);
+                format!("r{}'{}'{}", hashes, arg, hashes)
+            }
+            Shell::Nu => format!("'{}'", arg),
+        }
+    }
+}
+
+// PowerShell also ends a single quoted string at the curly single quotes, so
+// those are doubled along with the plain one.
+fn quote_pwsh(arg: &str) -> String {
+    let mut quoted = String::new();
+    for character in arg.chars() {
+        if "'\u{2018}\u{2019}\u{201A}\u{201B}".contains(character) {
+            quoted.push(character);
+        }
+        quoted.push(character);
+    }
+    quoted
+}
+
+// cmd expands %VARIABLE% and, with delayed expansion, !VARIABLE! even between
+// double quotes. Those characters are instead escaped with ^ outside of them.
+fn quote_cmd(arg: &str) -> String {
+    let mut quoted = String::new();
+    for character in arg.chars() {
+        match character {
+            '"' => quoted.push_str("\"\""),
+            '%' | '!' => {
+                quoted.push_str("\"^");
+                quoted.push(character);
+                quoted.push('"');
+            }
+            _ => quoted.push(character),
+        }
+    }
+    quoted
+}
+
+// A nu raw string r#'...'# ends at the first ' followed by as many #s as it was
+// opened with, so it has to be opened with more than any such run in the text.
+fn longest_hash_run(arg: &str) -> usize {
+    arg.split('\'')
+        .skip(1)
+        .map(|after| after.chars().take_while(|c| *c == '#').count())
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shell;
+
+    #[test]
+    fn plain_arguments_are_left_alone() {
+        for shell in [
+            Shell::Posix,
+            Shell::Fish,
+            Shell::Pwsh,
+            Shell::Cmd,
+            Shell::Nu,
+        ] {
+            assert_eq!(shell.quote("src/main.rs"), "src/main.rs");
+        }
+    }
+
+    #[test]
+    fn posix() {
+        assert_eq!(Shell::Posix.quote("a b"), "'a b'");
+        assert_eq!(Shell::Posix.quote("it's"), r"'it'\''s'");
+        assert_eq!(Shell::Posix.quote("$HOME; rm"), "'$HOME; rm'");
+        assert_eq!(Shell::Posix.quote(""), "''");
+    }
+
+    #[test]
+    fn fish() {
+        assert_eq!(Shell::Fish.quote("a b"), "'a b'");
+        assert_eq!(Shell::Fish.quote("it's"), r"'it\'s'");
+        assert_eq!(Shell::Fish.quote(r"a\'; rm"), r"'a\\\'; rm'");
+    }
+
+    #[test]
+    fn pwsh() {
+        assert_eq!(Shell::Pwsh.quote("a b"), "'a b'");
+        assert_eq!(Shell::Pwsh.quote("it's"), "'it''s'");
+        assert_eq!(
+            Shell::Pwsh.quote("a\u{2019}; rm -rf ~; \u{2019}"),
+            "'a\u{2019}\u{2019}; rm -rf ~; \u{2019}\u{2019}'"
+        );
+        assert_eq!(
+            Shell::Pwsh.quote("\u{2018}\u{201A}\u{201B}"),
+            "'\u{2018}\u{2018}\u{201A}\u{201A}\u{201B}\u{201B}'"
+        );
+    }
+
+    #[test]
+    fn cmd() {
+        assert_eq!(Shell::Cmd.quote("a b"), "\"a b\"");
+        assert_eq!(Shell::Cmd.quote("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(Shell::Cmd.quote("%PATH%"), "\"\"^%\"PATH\"^%\"\"");
+        assert_eq!(Shell::Cmd.quote("!PATH!"), "\"\"^!\"PATH\"^!\"\"");
+    }
+
+    #[test]
+    fn nu() {
+        assert_eq!(Shell::Nu.quote("a b"), "'a b'");
+        assert_eq!(Shell::Nu.quote("it's"), "r#'it's'#");
+        assert_eq!(
+            Shell::Nu.quote("x'#; rm -rf ~; r#'"),
+            "r##'x'#; rm -rf ~; r#''##"
+        );
+        assert_eq!(Shell::Nu.quote("a'###b"), "r####'a'###b'####");
+    }
+}
@@ -0,0 +1,638 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+
+use crate::filter::Filter;
+use crate::shell::Shell;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Hole {
+    Index(usize),
+    Name(String),
+    Range(usize, Option<usize>),
+    Rest,
+    Joined,
+    Env(String),
+}
+
+impl Hole {
+    fn name(&self) -> String {
+        match self {
+            Hole::Index(index) => index.to_string(),
+            Hole::Name(name) => name.clone(),
+            Hole::Range(start, Some(end)) => format!("{}..{}", start, end),
+            Hole::Range(start, None) => format!("{}..", start),
+            Hole::Rest => "@".to_string(),
+            Hole::Joined => "*".to_string(),
+            Hole::Env(name) => format!("env:{}", name),
+        }
+    }
+}
+
+impl fmt::Display for Hole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{{}}}", self.name())
+    }
+}
+
+// A hole as written in the template. {name=value} gives a default, {name!}
+// makes the hole required and filters follow it as in {name|upper}, with
+// {name|raw} skipping quoting the value. Environment variables are written
+// {env:NAME} and take a default as {env:NAME:-value}.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placeholder {
+    pub hole: Hole,
+    pub default: Option<String>,
+    pub required: bool,
+    pub filters: Vec<Filter>,
+}
+
+impl fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{{}", self.hole.name())?;
+        match (&self.hole, &self.default) {
+            (Hole::Env(_), Some(default)) => write!(f, ":-{}", default)?,
+            (_, Some(default)) => write!(f, "={}", default)?,
+            (_, None) => {}
+        }
+        if self.required {
+            write!(f, "!")?;
+        }
+        for filter in &self.filters {
+            write!(f, "|{}", filter)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Segment {
+    Text(String),
+    Hole(Placeholder),
+}
+
+// A value for a named hole that no argument filled.
+pub enum Variable {
+    // Worked out by okeydokey, so quoted as an argument would be.
+    Found(String),
+    // Written by the profile's author, so used as the hole's default is.
+    Default(String),
+}
+
+pub trait Variables {
+    fn get(&self, name: &str) -> Option<Variable>;
+}
+
+pub struct Filled {
+    pub command: String,
+    pub substitutions: Vec<(Hole, String)>,
+    pub appended: Vec<String>,
+    pub defaulted: Vec<Hole>,
+    pub unfilled: Vec<Hole>,
+    pub missing: Vec<Hole>,
+}
+
+// Anything in braces that isn't a number or a name is left alone, as are ${...}
+// and @{...} so that shell variable references and git revisions such as @{u}
+// keep working. Doubling the braces around a
+// hole, as in {{0}}, writes it out literally.
+pub fn parse(template: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(escaped) = after.strip_prefix('{') {
+            let content = escaped
+                .find("}}")
+                .map(|end| &escaped[..end])
+                .filter(|content| parse_placeholder(content).is_some());
+            if let Some(content) = content {
+                text.push('{');
+                text.push_str(content);
+                text.push('}');
+                rest = &escaped[content.len() + 2..];
+                continue;
+            }
+        }
+
+        let placeholder = after
+            .find('}')
+            .and_then(|end| Some((parse_placeholder(&after[..end])?, end)));
+
+        match placeholder {
+            Some((placeholder, end)) if !text.ends_with(['$', '@']) => {
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                segments.push(Segment::Hole(placeholder));
+                rest = &after[end + 1..];
+            }
+            _ => {
+                text.push('{');
+                rest = after;
+            }
+        }
+    }
+
+    text.push_str(rest);
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    segments
+}
+
+fn parse_placeholder(content: &str) -> Option<Placeholder> {
+    let mut parts = content.split('|');
+    let content = parts.next()?;
+    let filters = parts.map(Filter::parse).collect::<Option<Vec<_>>>()?;
+
+    if let Some(content) = content.strip_prefix("env:") {
+        let (name, default) = match content.split_once(":-") {
+            Some((name, default)) => (name, Some(default.to_string())),
+            None => (content, None),
+        };
+        return is_variable_name(name).then(|| Placeholder {
+            hole: Hole::Env(name.to_string()),
+            default,
+            required: false,
+            filters,
+        });
+    }
+
+    if let Some((hole, default)) = content.split_once('=') {
+        return Some(Placeholder {
+            hole: parse_hole(hole)?,
+            default: Some(default.to_string()),
+            required: false,
+            filters,
+        });
+    }
+
+    match content.strip_suffix('!') {
+        Some(hole) => Some(Placeholder {
+            hole: parse_hole(hole)?,
+            default: None,
+            required: true,
+            filters,
+        }),
+        None => Some(Placeholder {
+            hole: parse_hole(content)?,
+            default: None,
+            required: false,
+            filters,
+        }),
+    }
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut characters = name.chars();
+    characters
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && characters.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Like a shell's ${NAME:-default}, a variable that is set but empty counts as
+// unset.
+fn environment_variable(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn parse_index(content: &str) -> Option<usize> {
+    if !content.is_empty() && content.chars().all(|c| c.is_ascii_digit()) {
+        content.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn parse_hole(content: &str) -> Option<Hole> {
+    match content {
+        "@" => return Some(Hole::Rest),
+        "*" => return Some(Hole::Joined),
+        _ => {}
+    }
+    if let Some(index) = parse_index(content) {
+        return Some(Hole::Index(index));
+    }
+    if let Some((start, end)) = content.split_once("..") {
+        let start = parse_index(start)?;
+        return match end {
+            "" => Some(Hole::Range(start, None)),
+            end => {
+                let end = parse_index(end)?;
+                (end >= start).then_some(Hole::Range(start, Some(end)))
+            }
+        };
+    }
+
+    is_name(content).then(|| Hole::Name(content.to_string()))
+}
+
+pub fn is_name(content: &str) -> bool {
+    let mut characters = content.chars();
+    let starts_name = characters
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_');
+    starts_name && characters.all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+// Every hole in the template, positional ones first and environment variables
+// last. A hole written more than once takes the first default given and is
+// required if any use is. Filters apply per use, so those are left as the first
+// use has them.
+pub fn holes(template: &str) -> Vec<Placeholder> {
+    let mut placeholders: Vec<Placeholder> = Vec::new();
+    for segment in parse(template) {
+        let Segment::Hole(placeholder) = segment else {
+            continue;
+        };
+        match placeholders
+            .iter_mut()
+            .find(|existing| existing.hole == placeholder.hole)
+        {
+            Some(existing) => {
+                existing.default = existing.default.take().or(placeholder.default);
+                existing.required |= placeholder.required;
+            }
+            None => placeholders.push(placeholder),
+        }
+    }
+
+    placeholders.sort_by_key(|placeholder| match placeholder.hole {
+        Hole::Index(index) | Hole::Range(index, _) => (0, index),
+        Hole::Rest | Hole::Joined => (1, 0),
+        Hole::Name(_) => (2, 0),
+        Hole::Env(_) => (3, 0),
+    });
+    placeholders
+}
+
+// Arguments of the form name=value fill {name} when the template has such a
+// hole, and n=value the nth positional argument when a hole takes it. Everything
+// else is positional, filling whichever positions are still free in order: {n}
+// takes the nth of them, {a..b} the ath through the bth, and {@} or {*} those
+// past the highest numbered hole.
+// Arguments that no hole takes, whether past the highest numbered hole or in a
+// gap between numbered holes, are appended.
+// Named holes without an argument are looked up in variables before falling
+// back to their default, which a variable written in the profile overrides.
+// Given a shell, arguments and variables are quoted for it unless the hole is
+// raw. {*} joins its arguments into a single word before quoting. Defaults are
+// written by the profile's author and so are never quoted. A named hole that
+// nothing fills is written back out as it was.
+pub fn fill_in_arguments(
+    perferated_command: &str,
+    args: Vec<String>,
+    set: &[(String, String)],
+    variables: &dyn Variables,
+    shell: Option<Shell>,
+) -> Filled {
+    let segments = parse(perferated_command);
+    let holes = holes(perferated_command);
+    let is_named_hole = |name: &str| {
+        holes
+            .iter()
+            .any(|placeholder| placeholder.hole == Hole::Name(name.to_string()))
+    };
+
+    let mut named = set
+        .iter()
+        .filter(|(name, _)| is_named_hole(name))
+        .cloned()
+        .collect::<HashMap<_, _>>();
+    let used = rest_index(&holes);
+    let is_taken = |index: usize| {
+        holes
+            .iter()
+            .any(|placeholder| takes(&placeholder.hole, index, used))
+    };
+
+    let mut positional = BTreeMap::new();
+    let mut next = 0;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) if is_named_hole(name) => {
+                named.insert(name.to_string(), value.to_string());
+            }
+            Some((index, value)) if parse_index(index).is_some_and(is_taken) => {
+                positional.insert(parse_index(index).unwrap(), value.to_string());
+            }
+            _ => {
+                while positional.contains_key(&next) {
+                    next += 1;
+                }
+                positional.insert(next, arg);
+            }
+        }
+    }
+
+    let appended = positional
+        .iter()
+        .filter(|(index, _)| !is_taken(**index))
+        .map(|(_, arg)| arg.clone())
+        .collect::<Vec<_>>();
+    let slice = |start: usize, end: Option<usize>| {
+        let words = positional
+            .range(start..=end.unwrap_or(usize::MAX))
+            .map(|(_, arg)| arg.clone())
+            .collect::<Vec<_>>();
+        Some(words).filter(|words| !words.is_empty())
+    };
+    let quote = |value: &str| match shell {
+        Some(shell) => shell.quote(value),
+        None => value.to_string(),
+    };
+
+    let mut values = Vec::new();
+    let mut defaulted = Vec::new();
+    let mut unfilled = Vec::new();
+    let mut missing = Vec::new();
+    for placeholder in holes {
+        let hole = placeholder.hole;
+        // As in the shell, each use of an environment variable has its own
+        // default, so those are filled in per use below.
+        let mut default = match hole {
+            Hole::Env(_) => None,
+            _ => placeholder.default,
+        };
+        let words = match &hole {
+            Hole::Index(index) => positional.get(index).cloned().map(|value| vec![value]),
+            Hole::Name(name) => match named.get(name) {
+                Some(value) => Some(vec![value.clone()]),
+                None => match variables.get(name) {
+                    Some(Variable::Found(value)) => Some(vec![value]),
+                    Some(Variable::Default(value)) => {
+                        default = Some(value);
+                        None
+                    }
+                    None => None,
+                },
+            },
+            Hole::Range(start, end) => slice(*start, *end),
+            Hole::Rest => slice(used, None),
+            Hole::Joined => slice(used, None).map(|words| vec![words.join(" ")]),
+            Hole::Env(name) => environment_variable(name).map(|value| vec![value]),
+        };
+        let words = match (words, default) {
+            (Some(words), _) => words,
+            (None, Some(default)) => {
+                defaulted.push(hole.clone());
+                vec![default]
+            }
+            (None, None) if placeholder.required => {
+                missing.push(hole.clone());
+                Vec::new()
+            }
+            (None, None) => {
+                if let Hole::Name(_) = hole {
+                    unfilled.push(hole.clone());
+                }
+                Vec::new()
+            }
+        };
+        values.push((hole, words));
+    }
+
+    let mut command = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => command.push_str(&text),
+            Segment::Hole(placeholder) => {
+                let (_, words) = values
+                    .iter()
+                    .find(|(hole, _)| *hole == placeholder.hole)
+                    .unwrap();
+                if unfilled.contains(&placeholder.hole) {
+                    command.push_str(&placeholder.to_string());
+                    continue;
+                }
+                if let (Hole::Env(_), Some(default), true) =
+                    (&placeholder.hole, &placeholder.default, words.is_empty())
+                {
+                    let default = vec![default.clone()];
+                    command.push_str(&render(&placeholder, default, false, shell));
+                    continue;
+                }
+                let given =
+                    !defaulted.contains(&placeholder.hole) && !missing.contains(&placeholder.hole);
+                command.push_str(&render(&placeholder, words.clone(), given, shell));
+            }
+        }
+    }
+
+    for arg in &appended {
+        command = command + " " + &quote(arg);
+    }
+
+    Filled {
+        command,
+        substitutions: values
+            .into_iter()
+            .map(|(hole, words)| (hole, words.join(" ")))
+            .collect(),
+        appended,
+        defaulted,
+        unfilled,
+        missing,
+    }
+}
+
+// The first positional argument past every numbered hole, which is where {@}
+// and {*} start.
+pub fn rest_index(holes: &[Placeholder]) -> usize {
+    holes
+        .iter()
+        .filter_map(|placeholder| match placeholder.hole {
+            Hole::Index(index) | Hole::Range(_, Some(index)) => Some(index + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or_default()
+}
+
+// Whether the hole takes the positional argument at index, given the number of
+// arguments the numbered holes use.
+fn takes(hole: &Hole, index: usize, used: usize) -> bool {
+    match hole {
+        Hole::Index(taken) => *taken == index,
+        Hole::Range(start, end) => index >= *start && end.is_none_or(|end| index <= end),
+        Hole::Rest | Hole::Joined => index >= used,
+        Hole::Name(_) | Hole::Env(_) => false,
+    }
+}
+
+// Values that were given are quoted once the filters have run, unless one of
+// them took care of the quoting already.
+fn render(
+    placeholder: &Placeholder,
+    words: Vec<String>,
+    given: bool,
+    shell: Option<Shell>,
+) -> String {
+    let words = placeholder
+        .filters
+        .iter()
+        .fold(words, |words, filter| filter.apply(words, shell));
+    let quoted = placeholder.filters.iter().any(Filter::quotes);
+    match shell {
+        Some(shell) if given && !quoted => words
+            .iter()
+            .map(|word| shell.quote(word))
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => words.join(" "),
+    }
+}
+
+// For text that isn't given arguments, such as --prefix. Only environment
+// variables and named holes given with --set or known to variables are filled
+// in, any other holes are written back out as is.
+pub fn fill_in_variables(
+    text: &str,
+    set: &[(String, String)],
+    variables: &dyn Variables,
+    shell: Option<Shell>,
+) -> String {
+    let mut filled = String::new();
+    for segment in parse(text) {
+        let placeholder = match segment {
+            Segment::Text(text) => {
+                filled.push_str(&text);
+                continue;
+            }
+            Segment::Hole(placeholder) => placeholder,
+        };
+        let value = match &placeholder.hole {
+            Hole::Env(name) => Some(match environment_variable(name) {
+                Some(value) => Variable::Found(value),
+                None => Variable::Default(placeholder.default.clone().unwrap_or_default()),
+            }),
+            Hole::Name(name) => match set.iter().rev().find(|(set, _)| set == name) {
+                Some((_, value)) => Some(Variable::Found(value.clone())),
+                None => variables.get(name),
+            },
+            _ => None,
+        };
+        match value {
+            Some(Variable::Found(value)) => {
+                filled.push_str(&render(&placeholder, vec![value], true, shell))
+            }
+            Some(Variable::Default(value)) => {
+                filled.push_str(&render(&placeholder, vec![value], false, shell))
+            }
+            None => filled.push_str(&placeholder.to_string()),
+        }
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoVariables;
+
+    impl Variables for NoVariables {
+        fn get(&self, _: &str) -> Option<Variable> {
+            None
+        }
+    }
+
+    fn placeholder(hole: Hole) -> Placeholder {
+        Placeholder {
+            hole,
+            default: None,
+            required: false,
+            filters: Vec::new(),
+        }
+    }
+
+    fn fill(template: &str, args: &[&str]) -> Filled {
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        fill_in_arguments(template, args, &[], &NoVariables, Some(Shell::Posix))
+    }
+
+    #[test]
+    fn parses_holes_with_gaps() {
+        assert_eq!(
+            parse("echo {0} {2}"),
+            vec![
+                Segment::Text("echo ".to_string()),
+                Segment::Hole(placeholder(Hole::Index(0))),
+                Segment::Text(" ".to_string()),
+                Segment::Hole(placeholder(Hole::Index(2))),
+            ]
+        );
+    }
+
+    #[test]
+    fn doubled_braces_escape_holes() {
+        assert_eq!(
+            parse("echo {{0}} {{name}} {0}"),
+            vec![
+                Segment::Text("echo {0} {name} ".to_string()),
+                Segment::Hole(placeholder(Hole::Index(0))),
+            ]
+        );
+        assert_eq!(fill("echo {{0}} {0}", &["a"]).command, "echo {0} a");
+    }
+
+    #[test]
+    fn shell_syntax_is_left_alone() {
+        for template in ["echo ${HOME}", "git log @{u}..", "echo {a b}", "echo {}"] {
+            assert_eq!(parse(template), vec![Segment::Text(template.to_string())]);
+        }
+    }
+
+    #[test]
+    fn repeated_holes_are_merged() {
+        let holes = holes("{name=a} {0} {name=b} {0!}");
+        assert_eq!(holes.len(), 2);
+        assert_eq!(holes[0].hole, Hole::Index(0));
+        assert!(holes[0].required);
+        assert_eq!(holes[1].hole, Hole::Name("name".to_string()));
+        assert_eq!(holes[1].default.as_deref(), Some("a"));
+
+        assert_eq!(fill("echo {0} {0}", &["a b"]).command, "echo 'a b' 'a b'");
+    }
+
+    #[test]
+    fn arguments_in_gaps_are_appended() {
+        let filled = fill("echo {1}", &["a", "b"]);
+        assert_eq!(filled.command, "echo b a");
+        assert_eq!(filled.appended, vec!["a"]);
+
+        let filled = fill("echo {1..2} end", &["x", "y", "z", "w"]);
+        assert_eq!(filled.command, "echo y z end x w");
+
+        let filled = fill("echo {1} {@}", &["a", "b", "c"]);
+        assert_eq!(filled.command, "echo b c a");
+    }
+
+    #[test]
+    fn arguments_can_be_given_by_position() {
+        assert_eq!(fill("echo {0} {2}", &["2=c", "0=a"]).command, "echo a c");
+        assert_eq!(fill("echo {0} {1}", &["1=b", "a"]).command, "echo a b");
+        assert_eq!(fill("echo {0}", &["3=x"]).command, "echo '3=x'");
+    }
+
+    #[test]
+    fn environment_defaults_are_per_use() {
+        let filled = fill(
+            "echo {env:OKEYDOKEY_UNSET:-a} {env:OKEYDOKEY_UNSET} {env:OKEYDOKEY_UNSET:-b}",
+            &[],
+        );
+        assert_eq!(filled.command, "echo a  b");
+    }
+
+    #[test]
+    fn unfilled_named_holes_are_left_as_written() {
+        let filled = fill("awk '{print}' {file|upper}", &[]);
+        assert_eq!(filled.command, "awk '{print}' {file|upper}");
+        assert_eq!(filled.unfilled.len(), 2);
+    }
+}